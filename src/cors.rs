@@ -0,0 +1,173 @@
+//! Configurable CORS for the REST and grpc sides of the multiplexer.
+//!
+//! [`crate::xai_rest_layer`] and [`crate::xai_grpc_layer`] used to bake in a fixed header list
+//! (what `crate::default_allowed_headers` returns) and the `xambit.io` / `localhost` origin
+//! predicate. [`CorsConfig`] pulls that out into something callers can configure: which origins
+//! to allow, which headers, and which methods per protocol. The original behavior is still
+//! available as [`CorsConfig::xambit_default`].
+
+use std::sync::Arc;
+
+use http::{request::Parts, HeaderName, HeaderValue, Method};
+use tonic::{service::interceptor::InterceptorLayer, Status};
+use tower::layer::util::{Identity, Stack};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::default_allowed_headers;
+
+type OriginPredicate = Arc<dyn Fn(&HeaderValue, &Parts) -> bool + Send + Sync>;
+
+/// A reusable origin-matching rule for [`CorsConfig::allow_origin`].
+#[derive(Clone)]
+pub struct OriginMatcher(OriginPredicate);
+
+impl OriginMatcher {
+    /// Matches an `Origin` header exactly equal to `origin`.
+    pub fn exact(origin: impl Into<Vec<u8>>) -> Self {
+        let origin = origin.into();
+        Self(Arc::new(move |value: &HeaderValue, _: &Parts| {
+            value.as_bytes() == origin.as_slice()
+        }))
+    }
+
+    /// Matches an `Origin` header ending with `suffix`, e.g. a shared apex domain.
+    pub fn suffix(suffix: impl Into<Vec<u8>>) -> Self {
+        let suffix = suffix.into();
+        Self(Arc::new(move |value: &HeaderValue, _: &Parts| {
+            value.as_bytes().ends_with(&suffix)
+        }))
+    }
+
+    /// Matches any `http://localhost` origin, for local development.
+    pub fn localhost() -> Self {
+        Self(Arc::new(|value: &HeaderValue, _: &Parts| {
+            value.as_bytes().starts_with(b"http://localhost")
+        }))
+    }
+
+    /// Matches an empty `Origin` header, as sent by same-origin and non-browser requests.
+    pub fn empty() -> Self {
+        Self(Arc::new(|value: &HeaderValue, _: &Parts| value.is_empty()))
+    }
+
+    /// Matches using an arbitrary predicate, for deployments with bespoke origin rules.
+    pub fn predicate<F>(f: F) -> Self
+    where
+        F: Fn(&HeaderValue, &Parts) -> bool + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+}
+
+/// The allowed origins, methods and headers used to build the REST and grpc CORS layers.
+///
+/// Start from [`CorsConfig::new`] for an empty configuration, or [`CorsConfig::xambit_default`]
+/// to reproduce the behavior this crate shipped with before any of this was configurable.
+#[derive(Clone)]
+pub struct CorsConfig {
+    allowed_origins: Vec<OriginMatcher>,
+    allowed_headers: Vec<HeaderName>,
+    rest_methods: Vec<Method>,
+    grpc_methods: Vec<Method>,
+}
+
+impl CorsConfig {
+    /// An empty configuration: no origins, methods or headers are allowed until added.
+    pub fn new() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_headers: Vec::new(),
+            rest_methods: Vec::new(),
+            grpc_methods: Vec::new(),
+        }
+    }
+
+    /// The origins, headers and methods this crate allowed before they became configurable:
+    /// empty origin, any origin ending in `xambit.io`, or `http://localhost`.
+    pub fn xambit_default() -> Self {
+        Self::new()
+            .allow_origin(OriginMatcher::empty())
+            .allow_origin(OriginMatcher::suffix("xambit.io"))
+            .allow_origin(OriginMatcher::localhost())
+            .allow_headers(default_allowed_headers())
+            .rest_methods([
+                Method::POST,
+                Method::PUT,
+                Method::DELETE,
+                Method::GET,
+                Method::OPTIONS,
+            ])
+            .grpc_methods([Method::POST])
+    }
+
+    /// Allows an additional origin-matching rule; a request's origin must match at least one.
+    pub fn allow_origin(mut self, matcher: OriginMatcher) -> Self {
+        self.allowed_origins.push(matcher);
+        self
+    }
+
+    /// Allows an additional request header.
+    pub fn allow_header(mut self, header: HeaderName) -> Self {
+        self.allowed_headers.push(header);
+        self
+    }
+
+    /// Allows a batch of request headers.
+    pub fn allow_headers(mut self, headers: impl IntoIterator<Item = HeaderName>) -> Self {
+        self.allowed_headers.extend(headers);
+        self
+    }
+
+    /// Sets the methods allowed on the REST side of the multiplexer.
+    pub fn rest_methods(mut self, methods: impl IntoIterator<Item = Method>) -> Self {
+        self.rest_methods = methods.into_iter().collect();
+        self
+    }
+
+    /// Sets the methods allowed on the grpc side of the multiplexer.
+    pub fn grpc_methods(mut self, methods: impl IntoIterator<Item = Method>) -> Self {
+        self.grpc_methods = methods.into_iter().collect();
+        self
+    }
+
+    fn allow_origin_layer(&self) -> AllowOrigin {
+        let matchers = self.allowed_origins.clone();
+        AllowOrigin::predicate(move |origin: &HeaderValue, parts: &Parts| {
+            matchers.iter().any(|matcher| (matcher.0)(origin, parts))
+        })
+    }
+
+    fn base_layer(&self) -> CorsLayer {
+        CorsLayer::new()
+            .allow_headers(self.allowed_headers.clone())
+            .allow_origin(self.allow_origin_layer())
+    }
+
+    /// Builds the `tower` layer stack used for the REST side of the multiplexer.
+    pub fn rest_layer(&self) -> Stack<CorsLayer, Identity> {
+        tower::ServiceBuilder::new()
+            .layer(self.base_layer().allow_methods(self.rest_methods.clone()))
+            .into_inner()
+    }
+
+    /// Builds the `tower` layer stack used for the grpc side of the multiplexer, chaining
+    /// `extractor` in as an [`InterceptorLayer`] the same way [`crate::xai_grpc_layer`] did.
+    pub fn grpc_layer<F>(
+        &self,
+        extractor: F,
+    ) -> Stack<InterceptorLayer<F>, Stack<CorsLayer, Identity>>
+    where
+        F: FnMut(tonic::Request<()>) -> Result<tonic::Request<()>, Status>,
+    {
+        tower::ServiceBuilder::new()
+            .layer(self.base_layer().allow_methods(self.grpc_methods.clone()))
+            .layer(tonic::service::interceptor(extractor))
+            .into_inner()
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self::xambit_default()
+    }
+}
@@ -0,0 +1,242 @@
+//! A self-contained gRPC-Web <-> gRPC translation layer.
+//!
+//! gRPC and gRPC-Web share the same 5-byte length-prefixed message framing (a 1-byte
+//! compression flag followed by a 4-byte big-endian length, then the message), so talking to a
+//! gRPC-Web browser client is mostly a matter of (a) base64 (de|en)coding the body for the
+//! `-text` variant and (b) moving HTTP/2 trailers into a trailing frame in the body, since
+//! browsers can't read HTTP/2 trailers. This module does both without pulling in a base64 crate,
+//! since the whole point is to stay a small, dependency-free adapter in front of the grpc service.
+
+use axum::body::Body;
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::future::BoxFuture;
+use http::{HeaderMap, HeaderValue, Request, Response, Version};
+use http_body_util::BodyExt;
+use tower::Service;
+
+use crate::CONTENT_TYPE;
+
+/// The MSB of a gRPC(-Web) frame's compression-flag byte marks it as a trailer frame rather
+/// than a message frame.
+const GRPC_WEB_TRAILER_FLAG: u8 = 0x80;
+
+/// Stashed on the translated response's extensions so [`MultiplexService`](crate::MultiplexService)
+/// can record `grpc-status` on its tracing span without re-parsing the trailer frame this module
+/// just embedded in the body.
+pub(crate) struct GrpcStatus(pub(crate) HeaderValue);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GrpcWebEncoding {
+    Binary,
+    Text,
+}
+
+/// Wraps a grpc `tower::Service` so it can also serve gRPC-Web clients (`application/grpc-web`,
+/// `application/grpc-web+proto` and `application/grpc-web-text`), translating requests and
+/// responses to and from plain gRPC framing at the edge.
+///
+/// Pass the wrapped service as the `grpc` argument to [`MultiplexService::new`](crate::MultiplexService::new)
+/// to opt in; requests that aren't gRPC-Web are forwarded to the inner service unchanged.
+#[derive(Debug, Clone)]
+pub struct GrpcWebService<S> {
+    inner: S,
+}
+
+/// Wraps `inner` so it can also serve gRPC-Web clients. See [`GrpcWebService`].
+pub fn grpc_web<S>(inner: S) -> GrpcWebService<S> {
+    GrpcWebService { inner }
+}
+
+fn encoding_of<B>(req: &Request<B>) -> Option<GrpcWebEncoding> {
+    let content_type = req.headers().get(CONTENT_TYPE)?.as_bytes();
+    if content_type.starts_with(b"application/grpc-web-text") {
+        Some(GrpcWebEncoding::Text)
+    } else if content_type.starts_with(b"application/grpc-web") {
+        Some(GrpcWebEncoding::Binary)
+    } else {
+        None
+    }
+}
+
+impl<S> Service<Request<Body>> for GrpcWebService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        let Some(encoding) = encoding_of(&req) else {
+            let future = self.inner.call(req);
+            return Box::pin(future);
+        };
+
+        // mark the request as HTTP/2 so the grpc stack doesn't reject a request that didn't
+        // actually arrive over a real HTTP/2 connection
+        *req.version_mut() = Version::HTTP_2;
+        req.headers_mut().remove(http::header::CONTENT_LENGTH);
+        req.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+
+        let mut inner = self.inner.clone();
+        Box::pin(async move {
+            let req = apply_request_encoding(req, encoding).await;
+            let res = inner.call(req).await?;
+            Ok(encode_grpc_web_response(res, encoding).await)
+        })
+    }
+}
+
+async fn apply_request_encoding(req: Request<Body>, encoding: GrpcWebEncoding) -> Request<Body> {
+    if encoding != GrpcWebEncoding::Text {
+        return req;
+    }
+
+    let (parts, body) = req.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => match decode_base64(&bytes) {
+            Some(decoded) => Body::from(decoded),
+            None => {
+                error!("grpc-web-text request body is not valid base64");
+                Body::empty()
+            }
+        },
+        Err(err) => {
+            error!("Error buffering grpc-web request body: {err:?}");
+            Body::empty()
+        }
+    };
+    Request::from_parts(parts, body)
+}
+
+async fn encode_grpc_web_response(res: Response<Body>, encoding: GrpcWebEncoding) -> Response<Body> {
+    let (mut parts, body) = res.into_parts();
+    let (data, trailers) = match drain_with_trailers(body).await {
+        Ok(drained) => drained,
+        Err(err) => {
+            error!("Error draining grpc-web response body: {err:?}");
+            (Bytes::new(), HeaderMap::new())
+        }
+    };
+
+    if let Some(status) = trailers.get("grpc-status").cloned() {
+        parts.extensions.insert(GrpcStatus(status));
+    }
+
+    let mut framed = BytesMut::from(&data[..]);
+    framed.extend_from_slice(&trailer_frame(&trailers));
+
+    let payload = match encoding {
+        GrpcWebEncoding::Text => Body::from(encode_base64(&framed)),
+        GrpcWebEncoding::Binary => Body::from(framed.freeze()),
+    };
+
+    parts.headers.remove(http::header::TRAILER);
+    parts.headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(match encoding {
+            GrpcWebEncoding::Text => "application/grpc-web-text",
+            GrpcWebEncoding::Binary => "application/grpc-web+proto",
+        }),
+    );
+
+    Response::from_parts(parts, payload)
+}
+
+async fn drain_with_trailers(body: Body) -> Result<(Bytes, HeaderMap), axum::Error> {
+    let collected = body.collect().await?;
+    let trailers = collected.trailers().cloned().unwrap_or_default();
+    Ok((collected.to_bytes(), trailers))
+}
+
+fn trailer_frame(trailers: &HeaderMap) -> Bytes {
+    let mut text = String::new();
+    for (name, value) in trailers {
+        if let Ok(value) = value.to_str() {
+            text.push_str(name.as_str());
+            text.push_str(": ");
+            text.push_str(value);
+            text.push_str("\r\n");
+        }
+    }
+
+    let payload = text.into_bytes();
+    let mut frame = BytesMut::with_capacity(5 + payload.len());
+    frame.put_u8(GRPC_WEB_TRAILER_FLAG);
+    frame.put_u32(payload.len() as u32);
+    frame.extend_from_slice(&payload);
+    frame.freeze()
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn encode_base64(input: &[u8]) -> String {
+    let mut out = String::with_capacity((input.len() + 2) / 3 * 4);
+    for chunk in input.chunks(3) {
+        let b0 = chunk[0];
+        let b1 = chunk.get(1).copied().unwrap_or(0);
+        let b2 = chunk.get(2).copied().unwrap_or(0);
+        let n = (u32::from(b0) << 16) | (u32::from(b1) << 8) | u32::from(b2);
+
+        out.push(BASE64_ALPHABET[(n >> 18 & 0x3f) as usize] as char);
+        out.push(BASE64_ALPHABET[(n >> 12 & 0x3f) as usize] as char);
+        out.push(if chunk.len() > 1 {
+            BASE64_ALPHABET[(n >> 6 & 0x3f) as usize] as char
+        } else {
+            '='
+        });
+        out.push(if chunk.len() > 2 {
+            BASE64_ALPHABET[(n & 0x3f) as usize] as char
+        } else {
+            '='
+        });
+    }
+    out
+}
+
+fn decode_base64(input: &[u8]) -> Option<Vec<u8>> {
+    fn value(byte: u8) -> Option<u32> {
+        match byte {
+            b'A'..=b'Z' => Some(u32::from(byte - b'A')),
+            b'a'..=b'z' => Some(u32::from(byte - b'a') + 26),
+            b'0'..=b'9' => Some(u32::from(byte - b'0') + 52),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    let filtered: Vec<u8> = input
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    if filtered.is_empty() || filtered.len() % 4 != 0 {
+        return None;
+    }
+
+    let mut out = Vec::with_capacity(filtered.len() / 4 * 3);
+    for chunk in filtered.chunks(4) {
+        let pad = chunk.iter().filter(|&&b| b == b'=').count();
+        let mut n = 0u32;
+        for &byte in chunk {
+            n <<= 6;
+            if byte != b'=' {
+                n |= value(byte)?;
+            }
+        }
+        let bytes = n.to_be_bytes();
+        out.extend_from_slice(&bytes[1..4 - pad]);
+    }
+    Some(out)
+}
@@ -0,0 +1,77 @@
+//! A small middleware pipeline in front of the grpc service.
+//!
+//! `xai_grpc_layer`/[`CorsConfig::grpc_layer`](crate::CorsConfig::grpc_layer) take a single
+//! `FnMut(tonic::Request<()>) -> Result<tonic::Request<()>, Status>` extractor. Real auth or
+//! routing usually needs more than one such stage - add a header, reject based on the peer
+//! address, strip something another stage added - so [`InterceptorChain`] lets callers chain
+//! several of them and runs them in order, short-circuiting on the first `Err`.
+//!
+//! [`InterceptorChain`] itself implements that same `FnMut` signature (via the crate's
+//! `unboxed_closures`/`fn_traits` features), so it slots directly into `grpc_layer` in place of
+//! a single closure.
+
+use std::sync::Arc;
+
+use tonic::{Request, Status};
+
+/// A single stage in an [`InterceptorChain`]: inspects or mutates the request's metadata and may
+/// short-circuit the call by returning `Err`. A stage gets the `tonic::Request<()>` itself, so
+/// it can read the connection's remote address for IP-based decisions via the standard
+/// `req.remote_addr()` - tonic attaches it as connection info before the interceptor ever runs.
+pub trait InterceptorStage: Send + Sync {
+    fn intercept(&self, req: Request<()>) -> Result<Request<()>, Status>;
+}
+
+impl<F> InterceptorStage for F
+where
+    F: Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync,
+{
+    fn intercept(&self, req: Request<()>) -> Result<Request<()>, Status> {
+        self(req)
+    }
+}
+
+/// Chains multiple [`InterceptorStage`]s into one extractor for `xai_grpc_layer`/
+/// [`CorsConfig::grpc_layer`](crate::CorsConfig::grpc_layer). Stages run in the order they were
+/// added; the first one to return `Err` cancels the call and the rest don't run.
+#[derive(Clone, Default)]
+pub struct InterceptorChain {
+    stages: Vec<Arc<dyn InterceptorStage>>,
+}
+
+impl InterceptorChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a stage that can mutate the request's [`MetadataMap`](tonic::metadata::MetadataMap)
+    /// or cancel the call by returning `Err`.
+    pub fn chain<F>(mut self, stage: F) -> Self
+    where
+        F: Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static,
+    {
+        self.stages.push(Arc::new(stage));
+        self
+    }
+
+    fn run(&self, mut req: Request<()>) -> Result<Request<()>, Status> {
+        for stage in &self.stages {
+            req = stage.intercept(req)?;
+        }
+        Ok(req)
+    }
+}
+
+impl FnOnce<(Request<()>,)> for InterceptorChain {
+    type Output = Result<Request<()>, Status>;
+
+    extern "rust-call" fn call_once(self, args: (Request<()>,)) -> Self::Output {
+        self.run(args.0)
+    }
+}
+
+impl FnMut<(Request<()>,)> for InterceptorChain {
+    extern "rust-call" fn call_mut(&mut self, args: (Request<()>,)) -> Self::Output {
+        self.run(args.0)
+    }
+}
@@ -1,35 +1,50 @@
-#![feature(unboxed_closures)]
+#![feature(unboxed_closures, fn_traits)]
 
 use axum::{
+    body::Body,
     http::Request,
     response::{IntoResponse, Response},
 };
 use futures::{future::BoxFuture, ready};
-use http::{
-    header::{HeaderName, ACCEPT, CONTENT_TYPE, HOST},
-    request::Parts,
-    HeaderValue, Method,
-};
+use http::header::{HeaderName, ACCEPT, CONTENT_TYPE, HOST};
 
 use std::{
     convert::Infallible,
     task::{Context, Poll},
+    time::Instant,
 };
 use tonic::{service::interceptor::InterceptorLayer, Status};
 use tower::{
     layer::util::{Identity, Stack},
-    Service,
+    BoxError, Service,
 };
-use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::cors::CorsLayer;
+use tracing::Span;
 
 #[macro_use]
 extern crate log;
 
+mod cors;
+mod grpc_web;
+mod interceptor;
+mod metrics;
+mod trace;
+
+pub use cors::{CorsConfig, OriginMatcher};
+pub use grpc_web::grpc_web;
+pub use interceptor::{InterceptorChain, InterceptorStage};
+pub use metrics::MultiplexMetrics;
+
+use metrics::Protocol;
+use tracing::Instrument;
+
 pub struct MultiplexService<A, B> {
     rest: A,
     rest_ready: bool,
     grpc: B,
     grpc_ready: bool,
+    metrics: Option<MultiplexMetrics>,
+    tracing_enabled: bool,
 }
 
 impl<A, B> MultiplexService<A, B> {
@@ -39,8 +54,25 @@ impl<A, B> MultiplexService<A, B> {
             rest_ready: false,
             grpc,
             grpc_ready: false,
+            metrics: None,
+            tracing_enabled: false,
         }
     }
+
+    /// Records a request counter, an in-flight gauge and a latency histogram for every request,
+    /// labeled by protocol. See [`MultiplexMetrics`].
+    pub fn with_metrics(mut self, metrics: MultiplexMetrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Emits one `tracing` span per request, carrying the chosen protocol, method, path, matched
+    /// content-type, status, duration and - for grpc/grpc-web - the `grpc-status`. See the
+    /// `trace` module.
+    pub fn with_tracing(mut self) -> Self {
+        self.tracing_enabled = true;
+        self
+    }
 }
 
 impl<A, B> Clone for MultiplexService<A, B>
@@ -55,18 +87,22 @@ where
             // the cloned services probably wont be ready
             rest_ready: false,
             grpc_ready: false,
+            metrics: self.metrics.clone(),
+            tracing_enabled: self.tracing_enabled,
         }
     }
 }
 
-impl<A, B> Service<Request<hyper::body::Body>> for MultiplexService<A, B>
+impl<A, B, ReqBody> Service<Request<ReqBody>> for MultiplexService<A, B>
 where
-    A: Service<Request<hyper::body::Body>, Error = Infallible>,
+    A: Service<Request<Body>, Error = Infallible>,
     A::Response: IntoResponse,
     A::Future: Send + 'static,
-    B: Service<Request<hyper::body::Body>>,
+    B: Service<Request<Body>>,
     B::Response: IntoResponse,
     B::Future: Send + 'static,
+    ReqBody: http_body::Body<Data = bytes::Bytes> + Send + 'static,
+    ReqBody::Error: Into<BoxError>,
 {
     type Response = Response;
     type Error = B::Error;
@@ -91,7 +127,7 @@ where
         }
     }
 
-    fn call(&mut self, req: Request<hyper::body::Body>) -> Self::Future {
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
         // require users to call `poll_ready` first, if they don't we're allowed to panic
         // as per the `tower::Service` contract
         assert!(
@@ -103,31 +139,115 @@ where
             "rest service not ready. Did you forget to call `poll_ready`?"
         );
 
-        // if we get a grpc request call the grpc service, otherwise call the rest service
-        // when calling a service it becomes not-ready so we have drive readiness again
-        if is_grpc_request(&req) {
+        // normalize onto a single boxed body type so `self.rest`/`self.grpc` don't need to be
+        // generic themselves - this is what lets callers hand us a request from any `http_body`
+        // compatible server (hyper 1, axum's own `Body`, ...) while still composing with
+        // today's axum/tonic routers, which both speak `axum::body::Body`
+        let req = req.map(Body::new);
+
+        // if we get a grpc or grpc-web request call the grpc service, otherwise call the rest
+        // service. when calling a service it becomes not-ready so we have to drive readiness
+        // again. gRPC-Web requests are only translated if `self.grpc` was built with
+        // `grpc_web(..)`; plain gRPC and gRPC-Web are otherwise routed identically here.
+        //
+        // the content-type is looked up once and the two predicates share it, rather than each
+        // re-fetching it themselves; it's surfaced on the tracing span below instead of being
+        // printed directly, so it's only ever inspected, never logged on its own.
+        let content_type = content_type_bytes(&req);
+        let is_web = content_type.is_some_and(is_grpc_web_content_type);
+        let is_grpc = !is_web && content_type.is_some_and(|content_type| content_type.starts_with(b"application/grpc"));
+
+        if is_web {
             self.grpc_ready = false;
+            let guard = self.metrics.as_ref().map(|m| m.start(Protocol::GrpcWeb));
+            let span = trace::request_span(self.tracing_enabled, Protocol::GrpcWeb, req.method(), req.uri(), content_type);
+            let start = Instant::now();
             let future = self.grpc.call(req);
-            Box::pin(async move {
-                let res = future.await?;
-                Ok(res.into_response())
-            })
+            let instrumented_span = span.clone().unwrap_or_else(Span::none);
+            Box::pin(
+                async move {
+                    let res = future.await?.into_response();
+                    let res = record_grpc_completion(&span, guard, start, res);
+                    Ok(res)
+                }
+                .instrument(instrumented_span),
+            )
+        } else if is_grpc {
+            self.grpc_ready = false;
+            let guard = self.metrics.as_ref().map(|m| m.start(Protocol::Grpc));
+            let span = trace::request_span(self.tracing_enabled, Protocol::Grpc, req.method(), req.uri(), content_type);
+            let start = Instant::now();
+            let future = self.grpc.call(req);
+            let instrumented_span = span.clone().unwrap_or_else(Span::none);
+            Box::pin(
+                async move {
+                    let res = future.await?.into_response();
+                    let res = record_grpc_completion(&span, guard, start, res);
+                    Ok(res)
+                }
+                .instrument(instrumented_span),
+            )
         } else {
             self.rest_ready = false;
+            let guard = self.metrics.as_ref().map(|m| m.start(Protocol::Rest));
+            let span = trace::request_span(self.tracing_enabled, Protocol::Rest, req.method(), req.uri(), content_type);
+            let start = Instant::now();
             let future = self.rest.call(req);
-            Box::pin(async move {
-                let res = future.await.map_err(|err| {
-                    error!("Error during json await: {err:?}");
-                    match err {}
-                })?;
-                Ok(res.into_response())
-            })
+            let instrumented_span = span.clone().unwrap_or_else(Span::none);
+            Box::pin(
+                async move {
+                    let res = future
+                        .await
+                        .map_err(|err| match err {})?
+                        .into_response();
+                    trace::record_response(&span, start, res.status());
+                    if let Some(guard) = guard {
+                        guard.finish(res.status());
+                    }
+                    Ok(res)
+                }
+                .instrument(instrumented_span),
+            )
+        }
+    }
+}
+
+/// Shared by the grpc and grpc-web branches: records span fields (status and duration) and
+/// finishes the metrics guard, then resolves the `grpc-status` for the span. gRPC-Web already
+/// collected its trailers into the [`grpc_web::GrpcStatus`] extension while translating the
+/// response; a trailers-only response (such as an interceptor's `Err`) carries it as a header;
+/// otherwise it arrives as an HTTP/2 trailer once the body finishes streaming, so that case
+/// wraps the body to record it asynchronously as the caller reads it.
+fn record_grpc_completion(
+    span: &Option<Span>,
+    guard: Option<metrics::InFlightGuard>,
+    start: Instant,
+    res: Response,
+) -> Response {
+    trace::record_response(span, start, res.status());
+    if let Some(guard) = guard {
+        guard.finish(res.status());
+    }
+
+    if let Some(grpc_web::GrpcStatus(status)) = res.extensions().get::<grpc_web::GrpcStatus>() {
+        if let Ok(status) = status.to_str() {
+            trace::record_grpc_status(span, status);
         }
+        return res;
     }
+
+    if let Some(status) = res.headers().get("grpc-status").and_then(|v| v.to_str().ok()) {
+        trace::record_grpc_status(span, status);
+        return res;
+    }
+
+    let (parts, body) = res.into_parts();
+    let body = Body::new(trace::GrpcStatusBody::new(body, span.clone()));
+    Response::from_parts(parts, body)
 }
 
-fn cors_layer_allow_header() -> CorsLayer {
-    CorsLayer::new().allow_headers([
+fn default_allowed_headers() -> Vec<HeaderName> {
+    vec![
         ACCEPT,
         HOST,
         CONTENT_TYPE,
@@ -145,57 +265,77 @@ fn cors_layer_allow_header() -> CorsLayer {
         HeaderName::from_static("x-provider"),
         HeaderName::from_static("x-grpc-web"),
         HeaderName::from_static("x-user-agent"),
-    ])
+    ]
 }
 
-fn is_grpc_request<B>(req: &Request<B>) -> bool {
+fn content_type_bytes<B>(req: &Request<B>) -> Option<&[u8]> {
     req.headers()
-        .get("content-type")
-        .map(|content_type| {
-            info!(
-                "{}",
-                String::from_utf8(content_type.as_bytes().to_vec()).unwrap()
-            );
-            content_type.as_bytes()
-        })
-        .filter(|content_type| content_type.starts_with(b"application/grpc"))
-        .is_some()
+        .get(CONTENT_TYPE)
+        .map(|content_type| content_type.as_bytes())
 }
 
+// browsers can't speak the HTTP/2 trailers that plain gRPC relies on, so they instead send
+// `application/grpc-web(+proto|-text)`; see the `grpc_web` module for the translation layer
+fn is_grpc_web_content_type(content_type: &[u8]) -> bool {
+    content_type.starts_with(b"application/grpc-web")
+}
+
+/// The REST `tower` layer stack this crate has always built: CORS restricted to `xambit.io`,
+/// `http://localhost` and empty origins. Equivalent to `CorsConfig::xambit_default().rest_layer()`;
+/// use [`CorsConfig`] directly to allow different origins, methods or headers.
 pub fn xai_rest_layer() -> Stack<CorsLayer, Identity> {
-    tower::ServiceBuilder::new()
-        .layer(
-            cors_layer_allow_header()
-                .allow_origin(AllowOrigin::predicate(|origin: &HeaderValue, _: &Parts| {
-                    origin.is_empty()
-                        || origin.as_bytes().ends_with(b"xambit.io")
-                        || origin.as_bytes().starts_with(b"http://localhost")
-                }))
-                .allow_methods([
-                    Method::POST,
-                    Method::PUT,
-                    Method::DELETE,
-                    Method::GET,
-                    Method::OPTIONS,
-                ]),
-        )
-        .into_inner()
+    CorsConfig::xambit_default().rest_layer()
 }
 
+/// The grpc `tower` layer stack this crate has always built, with `extractor` as a single
+/// [`InterceptorLayer`] stage. Equivalent to `CorsConfig::xambit_default().grpc_layer(extractor)`;
+/// use [`CorsConfig`] directly to allow different origins, methods or headers.
 pub fn xai_grpc_layer<F>(extractor: F) -> Stack<InterceptorLayer<F>, Stack<CorsLayer, Identity>>
 where
     F: FnMut(tonic::Request<()>) -> anyhow::Result<tonic::Request<()>, Status>,
 {
-    tower::ServiceBuilder::new()
-        .layer(
-            cors_layer_allow_header()
-                .allow_origin(AllowOrigin::predicate(|origin: &HeaderValue, _: &Parts| {
-                    origin.is_empty()
-                        || origin.as_bytes().ends_with(b"xambit.io")
-                        || origin.as_bytes().starts_with(b"http://localhost")
-                }))
-                .allow_methods([Method::POST]),
-        )
-        .layer(tonic::service::interceptor(extractor))
-        .into_inner()
+    CorsConfig::xambit_default().grpc_layer(extractor)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use bytes::Bytes;
+    use http_body_util::Full;
+    use tower::{service_fn, ServiceExt};
+
+    use super::*;
+
+    // a request body type that isn't `axum::body::Body`, to prove `MultiplexService` really is
+    // generic over `ReqBody` rather than secretly requiring axum's own type
+    fn body(data: &'static str) -> Full<Bytes> {
+        Full::new(Bytes::from_static(data.as_bytes()))
+    }
+
+    async fn text_response(body: &'static str) -> Result<Response, Infallible> {
+        Ok(Response::new(Body::from(body)))
+    }
+
+    #[tokio::test]
+    async fn routes_rest_and_grpc_over_a_generic_request_body() {
+        let rest = service_fn(|_req: Request<Body>| text_response("rest"));
+        let grpc = service_fn(|_req: Request<Body>| text_response("grpc"));
+        let mut svc = MultiplexService::new(rest, grpc);
+
+        let ready = svc.ready().await.unwrap();
+        let req = Request::builder().body(body("{}")).unwrap();
+        let res = ready.call(req).await.unwrap();
+        let bytes = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&bytes[..], b"rest");
+
+        let ready = svc.ready().await.unwrap();
+        let req = Request::builder()
+            .header(CONTENT_TYPE, "application/grpc")
+            .body(body(""))
+            .unwrap();
+        let res = ready.call(req).await.unwrap();
+        let bytes = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&bytes[..], b"grpc");
+    }
 }
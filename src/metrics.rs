@@ -0,0 +1,149 @@
+//! Optional per-protocol Prometheus metrics for [`MultiplexService`](crate::MultiplexService).
+//!
+//! Attach a [`MultiplexMetrics`] with [`MultiplexService::with_metrics`](crate::MultiplexService::with_metrics)
+//! to get a request counter, an in-flight gauge and a latency histogram, each labeled by
+//! protocol (`rest`, `grpc`, `grpc-web`) and, for the counter, response status class. Render
+//! the current values with [`MultiplexMetrics::render`] from a `/metrics` scrape handler.
+
+use std::time::Instant;
+
+use prometheus::{Encoder, HistogramVec, IntCounterVec, IntGaugeVec, Registry, TextEncoder};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Protocol {
+    Rest,
+    Grpc,
+    GrpcWeb,
+}
+
+impl Protocol {
+    fn as_str(self) -> &'static str {
+        match self {
+            Protocol::Rest => "rest",
+            Protocol::Grpc => "grpc",
+            Protocol::GrpcWeb => "grpc-web",
+        }
+    }
+}
+
+/// A Prometheus registry wired up with the multiplexer's request counter, in-flight gauge and
+/// latency histogram.
+#[derive(Clone)]
+pub struct MultiplexMetrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    in_flight: IntGaugeVec,
+    latency: HistogramVec,
+}
+
+impl MultiplexMetrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let requests = IntCounterVec::new(
+            prometheus::Opts::new(
+                "multiplex_requests_total",
+                "Total requests handled by the multiplexer, by protocol and response status class",
+            ),
+            &["protocol", "status"],
+        )
+        .expect("metric name and labels are valid");
+        let in_flight = IntGaugeVec::new(
+            prometheus::Opts::new(
+                "multiplex_requests_in_flight",
+                "Requests currently being handled by the multiplexer, by protocol",
+            ),
+            &["protocol"],
+        )
+        .expect("metric name and labels are valid");
+        let latency = HistogramVec::new(
+            prometheus::HistogramOpts::new(
+                "multiplex_request_duration_seconds",
+                "Request latency in seconds, by protocol",
+            ),
+            &["protocol"],
+        )
+        .expect("metric name and labels are valid");
+
+        registry
+            .register(Box::new(requests.clone()))
+            .expect("metric is only registered once");
+        registry
+            .register(Box::new(in_flight.clone()))
+            .expect("metric is only registered once");
+        registry
+            .register(Box::new(latency.clone()))
+            .expect("metric is only registered once");
+
+        Self {
+            registry,
+            requests,
+            in_flight,
+            latency,
+        }
+    }
+
+    /// Renders the current metrics in Prometheus text exposition format, for a `/metrics`
+    /// scrape handler.
+    pub fn render(&self) -> String {
+        let mut buf = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buf)
+            .expect("encoding our own counter/gauge/histogram metrics cannot fail");
+        String::from_utf8(buf).expect("prometheus text output is always valid utf8")
+    }
+
+    pub(crate) fn start(&self, protocol: Protocol) -> InFlightGuard {
+        self.in_flight.with_label_values(&[protocol.as_str()]).inc();
+        InFlightGuard {
+            metrics: self.clone(),
+            protocol,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for MultiplexMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Tracks one in-flight request; decrements the in-flight gauge on drop and, if [`finish`] is
+/// called, records the completed request's status and latency.
+///
+/// [`finish`]: InFlightGuard::finish
+pub(crate) struct InFlightGuard {
+    metrics: MultiplexMetrics,
+    protocol: Protocol,
+    start: Instant,
+}
+
+impl InFlightGuard {
+    pub(crate) fn finish(self, status: http::StatusCode) {
+        let class = match status.as_u16() {
+            100..=199 => "1xx",
+            200..=299 => "2xx",
+            300..=399 => "3xx",
+            400..=499 => "4xx",
+            _ => "5xx",
+        };
+        self.metrics
+            .requests
+            .with_label_values(&[self.protocol.as_str(), class])
+            .inc();
+        self.metrics
+            .latency
+            .with_label_values(&[self.protocol.as_str()])
+            .observe(self.start.elapsed().as_secs_f64());
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.metrics
+            .in_flight
+            .with_label_values(&[self.protocol.as_str()])
+            .dec();
+    }
+}
@@ -0,0 +1,105 @@
+//! Optional structured tracing for [`MultiplexService`](crate::MultiplexService).
+//!
+//! Enable it with [`MultiplexService::with_tracing`](crate::MultiplexService::with_tracing) to
+//! get one [`tracing`] span per request, carrying the chosen protocol, method, path, matched
+//! content-type and (once the backend responds) status, duration and - for grpc/grpc-web - the
+//! `grpc-status`. This replaces the old ad-hoc `info!`/`error!` prints with a single span per
+//! request lifecycle.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use bytes::Bytes;
+use futures::ready;
+use http::{Method, StatusCode, Uri};
+use http_body::{Body, Frame};
+use tracing::{field, Span};
+
+use crate::metrics::Protocol;
+
+/// Starts the per-request span for `protocol`, if tracing is enabled. Returns `None` (a no-op)
+/// when it isn't, so callers can thread the `Option<Span>` through without branching.
+pub(crate) fn request_span(
+    enabled: bool,
+    protocol: Protocol,
+    method: &Method,
+    uri: &Uri,
+    content_type: Option<&[u8]>,
+) -> Option<Span> {
+    if !enabled {
+        return None;
+    }
+    let content_type = content_type.map(String::from_utf8_lossy);
+    Some(tracing::info_span!(
+        "multiplex_request",
+        protocol = protocol_str(protocol),
+        method = %method,
+        path = %uri.path(),
+        content_type = content_type.as_deref(),
+        status = field::Empty,
+        duration_ms = field::Empty,
+        grpc_status = field::Empty,
+    ))
+}
+
+fn protocol_str(protocol: Protocol) -> &'static str {
+    match protocol {
+        Protocol::Rest => "rest",
+        Protocol::Grpc => "grpc",
+        Protocol::GrpcWeb => "grpc-web",
+    }
+}
+
+/// Records the response status and elapsed time on `span`, a no-op if `span` is `None`.
+pub(crate) fn record_response(span: &Option<Span>, start: Instant, status: StatusCode) {
+    if let Some(span) = span {
+        span.record("status", status.as_u16());
+        span.record("duration_ms", start.elapsed().as_millis() as u64);
+    }
+}
+
+/// Records the `grpc-status` trailer/header value on `span`, a no-op if `span` is `None`.
+pub(crate) fn record_grpc_status(span: &Option<Span>, grpc_status: &str) {
+    if let Some(span) = span {
+        span.record("grpc_status", grpc_status);
+    }
+}
+
+/// Wraps a plain gRPC response body so that when its HTTP/2 trailers arrive, the `grpc-status`
+/// they carry gets recorded on `span` before the trailer frame is forwarded on to the caller.
+pub(crate) struct GrpcStatusBody<B> {
+    inner: B,
+    span: Option<Span>,
+}
+
+impl<B> GrpcStatusBody<B> {
+    pub(crate) fn new(inner: B, span: Option<Span>) -> Self {
+        Self { inner, span }
+    }
+}
+
+impl<B> Body for GrpcStatusBody<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        if let Some(Ok(frame)) = &frame {
+            if let Some(trailers) = frame.trailers_ref() {
+                if let Some(status) = trailers.get("grpc-status").and_then(|v| v.to_str().ok()) {
+                    record_grpc_status(&self.span, status);
+                }
+            }
+        }
+        Poll::Ready(frame)
+    }
+}